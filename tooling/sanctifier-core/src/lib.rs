@@ -1,14 +1,134 @@
+use std::collections::HashMap;
+
 use soroban_sdk::Env;
-use syn::{parse_str, File, Item, Type, Fields, Meta, ExprMethodCall, Macro};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    parse_str, Attribute, Block, Expr, ExprBinary, ExprIndex, ExprMethodCall, Fields, File,
+    GenericArgument, Ident, ImplItemFn, Item, ItemEnum, ItemFn, ItemStruct, Lit, LitStr, Macro,
+    Meta, Path, PathArguments, StmtMacro, Token, Type,
+};
 use syn::visit::{self, Visit};
+use quote::ToTokens;
 use serde::Serialize;
 use thiserror::Error;
 
+/// Lint level for a diagnostic code, mirroring rustc's `allow`/`warn`/`deny`/
+/// `forbid` ladder. `forbid` is sticky: an inner `allow` cannot lower it.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+    Forbid,
+}
+
+/// Estimated byte cost of a single struct field, surfaced so authors can see
+/// exactly which field pushed an entry over the ledger limit.
+#[derive(Debug, Serialize)]
+pub struct FieldSize {
+    pub field: String,
+    pub bytes: usize,
+}
+
 #[derive(Debug, Serialize)]
 pub struct SizeWarning {
     pub struct_name: String,
     pub estimated_size: usize,
     pub limit: usize,
+    pub breakdown: Vec<FieldSize>,
+    pub span: Span,
+    pub level: LintLevel,
+}
+
+/// Severity of a unified [`Diagnostic`], mirroring the levels rustc emits.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// A source location, expressed as a half-open range of 1-based lines and
+/// 0-based columns so an editor can underline the exact token.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+impl Span {
+    /// Capture the full extent of a `proc_macro2::Span`, not just its start
+    /// line, so the range covers the whole offending token.
+    fn of(span: proc_macro2::Span) -> Self {
+        let start = span.start();
+        let end = span.end();
+        Span {
+            start_line: start.line,
+            start_col: start.column,
+            end_line: end.line,
+            end_col: end.column,
+        }
+    }
+}
+
+/// Two storage keys that resolve to the same slot within one storage domain.
+#[derive(Debug, Serialize)]
+pub struct CollisionWarning {
+    pub key_a: String,
+    pub key_b: String,
+    pub storage_domain: String,
+    pub span: Span,
+}
+
+/// An optional fix an editor can apply in place to resolve a diagnostic.
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub replacement: String,
+}
+
+/// A single finding in the unified diagnostic stream. Every analyzer pass
+/// lowers its own warning type into this shape so editors and CI consume one
+/// format.
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub span: Span,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<Suggestion>,
+}
+
+/// Index of user-defined types, built in a first pass so the size estimator can
+/// resolve `Type::Path` references back into the struct/enum they name.
+struct TypeIndex<'ast> {
+    structs: HashMap<String, &'ast ItemStruct>,
+    enums: HashMap<String, &'ast ItemEnum>,
+}
+
+impl<'ast> TypeIndex<'ast> {
+    fn build(file: &'ast File) -> Self {
+        let mut structs = HashMap::new();
+        let mut enums = HashMap::new();
+        for item in &file.items {
+            match item {
+                Item::Struct(s) => {
+                    structs.insert(s.ident.to_string(), s);
+                }
+                Item::Enum(e) => {
+                    enums.insert(e.ident.to_string(), e);
+                }
+                _ => {}
+            }
+        }
+        Self { structs, enums }
+    }
 }
 
 #[derive(Debug, Serialize, Clone, Copy)]
@@ -16,6 +136,12 @@ pub enum PatternType {
     Panic,
     Unwrap,
     Expect,
+    /// Integer `+ - *` (and `/ %`) that traps on overflow.
+    ArithmeticOverflow,
+    /// `v[i]` indexing that traps when `i` is out of bounds.
+    IndexPanic,
+    /// `/` or `%` whose divisor is not a proven-nonzero literal.
+    DivisionByZero,
 }
 
 #[derive(Debug, Serialize)]
@@ -23,6 +149,85 @@ pub struct UnsafePattern {
     pub pattern_type: PatternType,
     pub line: usize,
     pub snippet: String,
+    pub span: Span,
+    pub level: LintLevel,
+    /// The suggested checked/fallible alternative, when one applies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    /// Name of the macro this finding was resolved from, when it lives inside a
+    /// macro invocation rather than in plain source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin_macro: Option<String>,
+}
+
+impl PatternType {
+    /// Stable diagnostic code for this pattern, used by the unified stream.
+    fn code(self) -> &'static str {
+        match self {
+            PatternType::Panic => "S001",
+            PatternType::Unwrap => "S002",
+            PatternType::Expect => "S003",
+            PatternType::ArithmeticOverflow => "S004",
+            PatternType::IndexPanic => "S005",
+            PatternType::DivisionByZero => "S006",
+        }
+    }
+
+    /// Human-readable lint name, as written in `#[sanctify::allow(..)]`.
+    fn lint_name(self) -> &'static str {
+        match self {
+            PatternType::Panic => "unsafe_panic",
+            PatternType::Unwrap => "unsafe_unwrap",
+            PatternType::Expect => "unsafe_expect",
+            PatternType::ArithmeticOverflow => "arithmetic_overflow",
+            PatternType::IndexPanic => "index_panic",
+            PatternType::DivisionByZero => "division_by_zero",
+        }
+    }
+}
+
+/// Lint name for the ledger-size diagnostic (`S010`).
+const LEDGER_SIZE_LINT: &str = "ledger_size";
+
+/// Lint name for the storage-collision diagnostic (`S020`).
+const STORAGE_COLLISION_LINT: &str = "storage_collision";
+
+/// Map an effective lint level to the severity shown in the diagnostic stream.
+/// `allow` findings are dropped before this point, so they never appear.
+fn severity_for(level: LintLevel) -> Severity {
+    match level {
+        LintLevel::Deny | LintLevel::Forbid => Severity::Error,
+        _ => Severity::Warning,
+    }
+}
+
+/// Parse `#[sanctify::allow(..)]` / `warn` / `deny` / `forbid` attributes into a
+/// lint-name -> level map. Non-`sanctify` attributes are ignored.
+fn parse_lint_attrs(attrs: &[Attribute]) -> HashMap<String, LintLevel> {
+    let mut map = HashMap::new();
+    for attr in attrs {
+        let path = attr.path();
+        if path.segments.len() != 2 || path.segments[0].ident != "sanctify" {
+            continue;
+        }
+        let level = match path.segments[1].ident.to_string().as_str() {
+            "allow" => LintLevel::Allow,
+            "warn" => LintLevel::Warn,
+            "deny" => LintLevel::Deny,
+            "forbid" => LintLevel::Forbid,
+            _ => continue,
+        };
+        if let Meta::List(list) = &attr.meta {
+            if let Ok(names) =
+                list.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+            {
+                for name in names {
+                    map.insert(name.to_string(), level);
+                }
+            }
+        }
+    }
+    map
 }
 
 #[derive(Error, Debug)]
@@ -37,45 +242,408 @@ pub trait SanctifiedGuard {
     fn check_invariant(&self, env: &Env) -> Result<(), Error>;
 }
 
-struct UnsafeVisitor {
+struct UnsafeVisitor<'a> {
+    analyzer: &'a Analyzer,
     patterns: Vec<UnsafePattern>,
+    /// Stack of inline lint levels from enclosing items/expressions, outermost
+    /// first.
+    scope: Vec<HashMap<String, LintLevel>>,
+    /// Whether to descend into macro bodies. Disabled for the sub-visitor that
+    /// scans an expansion, so resolution stops after one level of hygiene.
+    scan_macros: bool,
+}
+
+impl<'a> UnsafeVisitor<'a> {
+    /// Record a finding unless it is suppressed (`allow`) by the enclosing
+    /// scope, stamping it with its effective lint level.
+    fn record(
+        &mut self,
+        pattern_type: PatternType,
+        span: proc_macro2::Span,
+        snippet: String,
+        suggestion: Option<String>,
+    ) {
+        let level = self.analyzer.effective_level(pattern_type.lint_name(), &self.scope);
+        if level == LintLevel::Allow {
+            return;
+        }
+        self.patterns.push(UnsafePattern {
+            pattern_type,
+            line: span.start().line,
+            snippet,
+            span: Span::of(span),
+            level,
+            suggestion,
+            origin_macro: None,
+        });
+    }
+
+    /// Re-parse a macro's token stream as an expression, block, or
+    /// comma-separated list and run the unsafe walk over it, rebasing every
+    /// finding onto the invocation site and tagging it with the macro name.
+    /// No-ops when the tokens don't parse as any of those forms.
+    fn scan_macro_body(&mut self, mac: &Macro) {
+        let text = mac.tokens.to_string();
+        let mut inner = UnsafeVisitor {
+            analyzer: self.analyzer,
+            patterns: Vec::new(),
+            scope: self.scope.clone(),
+            scan_macros: false,
+        };
+        if let Ok(expr) = parse_str::<Expr>(&text) {
+            inner.visit_expr(&expr);
+        } else if let Ok(block) = parse_str::<Block>(&format!("{{ {} }}", text)) {
+            inner.visit_block(&block);
+        } else if let Ok(expr) = parse_str::<Expr>(&format!("({})", text)) {
+            inner.visit_expr(&expr);
+        } else {
+            return;
+        }
+
+        let site = mac.path.span();
+        let name = mac
+            .path
+            .segments
+            .last()
+            .map(|s| s.ident.to_string())
+            .unwrap_or_default();
+        for mut p in inner.patterns {
+            p.span = Span::of(site);
+            p.line = site.start().line;
+            p.origin_macro = Some(name.clone());
+            self.patterns.push(p);
+        }
+    }
+
+    /// Run `f` with `attrs`' lint overrides pushed onto the scope stack.
+    fn scoped<R>(&mut self, attrs: &[Attribute], f: impl FnOnce(&mut Self) -> R) -> R {
+        self.scope.push(parse_lint_attrs(attrs));
+        let out = f(self);
+        self.scope.pop();
+        out
+    }
 }
 
-impl<'ast> Visit<'ast> for UnsafeVisitor {
+impl<'ast, 'a> Visit<'ast> for UnsafeVisitor<'a> {
+    fn visit_item_fn(&mut self, i: &'ast ItemFn) {
+        self.scoped(&i.attrs, |v| visit::visit_item_fn(v, i));
+    }
+
+    fn visit_impl_item_fn(&mut self, i: &'ast ImplItemFn) {
+        self.scoped(&i.attrs, |v| visit::visit_impl_item_fn(v, i));
+    }
+
     fn visit_macro(&mut self, i: &'ast Macro) {
         if i.path.is_ident("panic") {
-            self.patterns.push(UnsafePattern {
-                pattern_type: PatternType::Panic,
-                line: i.path.segments[0].ident.span().start().line,
-                snippet: "panic!".to_string(),
-            });
+            let span = i.path.segments[0].ident.span();
+            self.record(PatternType::Panic, span, "panic!".to_string(), None);
+        }
+        // `syn` does not descend into token streams, so re-parse and scan the
+        // body ourselves (one level deep).
+        if self.scan_macros {
+            self.scan_macro_body(i);
         }
         visit::visit_macro(self, i);
     }
 
+    fn visit_stmt_macro(&mut self, i: &'ast StmtMacro) {
+        // `syn::Macro` has no `attrs` of its own — an attribute written on a
+        // macro used as a statement (e.g. `#[sanctify::allow(unsafe_panic)]
+        // panic!("...")`) attaches to the enclosing `StmtMacro` instead, so it
+        // must be pushed here rather than relying on `visit_macro`.
+        self.scoped(&i.attrs, |v| visit::visit_stmt_macro(v, i));
+    }
+
     fn visit_expr_method_call(&mut self, i: &'ast ExprMethodCall) {
         let method_name = i.method.to_string();
         if method_name == "unwrap" || method_name == "expect" {
-            self.patterns.push(UnsafePattern {
-                pattern_type: if method_name == "unwrap" { PatternType::Unwrap } else { PatternType::Expect },
-                line: i.method.span().start().line,
-                snippet: method_name,
+            let pattern_type = if method_name == "unwrap" {
+                PatternType::Unwrap
+            } else {
+                PatternType::Expect
+            };
+            // Honour an attribute written directly on the call expression.
+            self.scoped(&i.attrs, |v| {
+                v.record(pattern_type, i.method.span(), method_name, None);
             });
         }
         visit::visit_expr_method_call(self, i);
     }
+
+    fn visit_expr_binary(&mut self, i: &'ast ExprBinary) {
+        use syn::BinOp;
+        let (method, sym) = match i.op {
+            BinOp::Add(_) => ("checked_add", "+"),
+            BinOp::Sub(_) => ("checked_sub", "-"),
+            BinOp::Mul(_) => ("checked_mul", "*"),
+            BinOp::Div(_) => ("checked_div", "/"),
+            BinOp::Rem(_) => ("checked_rem", "%"),
+            _ => {
+                visit::visit_expr_binary(self, i);
+                return;
+            }
+        };
+        let is_div = matches!(i.op, BinOp::Div(_) | BinOp::Rem(_));
+        // Only flag when the operands look like they could be integers.
+        if looks_integer(&i.left) && looks_integer(&i.right) {
+            let span = i.op.span();
+            let suggestion = Some(format!("{}(..)", method));
+            // Honour an attribute written directly on the binary expression.
+            // `syn` never populates `ExprBinary::attrs` itself — a leading
+            // attribute on `a + b` parses onto the leftmost operand instead —
+            // so fall back to that via `leading_attrs`.
+            self.scoped(leading_attrs(i), |v| {
+                if is_div && !is_nonzero_int_literal(&i.right) {
+                    v.record(PatternType::DivisionByZero, span, sym.to_string(), suggestion);
+                } else {
+                    v.record(PatternType::ArithmeticOverflow, span, sym.to_string(), suggestion);
+                }
+            });
+        }
+        visit::visit_expr_binary(self, i);
+    }
+
+    fn visit_expr_index(&mut self, i: &'ast ExprIndex) {
+        // Honour an attribute written directly on the index expression.
+        self.scoped(&i.attrs, |v| {
+            v.record(
+                PatternType::IndexPanic,
+                i.bracket_token.span.join(),
+                "[..]".to_string(),
+                Some(".get(..)".to_string()),
+            );
+        });
+        visit::visit_expr_index(self, i);
+    }
+}
+
+/// Heuristic: treat an operand as possibly integer-typed unless it is an
+/// obviously non-integer literal (float/string/char/bool). Without full type
+/// inference this errs toward flagging, matching the tool's conservative stance.
+fn looks_integer(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(lit) => matches!(lit.lit, Lit::Int(_)),
+        Expr::Paren(p) => looks_integer(&p.expr),
+        Expr::Group(g) => looks_integer(&g.expr),
+        Expr::Unary(u) => looks_integer(&u.expr),
+        _ => true,
+    }
+}
+
+/// Whether `expr` is an integer literal proven to be nonzero, making a division
+/// by it safe from the divide-by-zero trap.
+fn is_nonzero_int_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(n) => n.base10_parse::<u128>().map(|v| v != 0).unwrap_or(false),
+            _ => false,
+        },
+        Expr::Paren(p) => is_nonzero_int_literal(&p.expr),
+        Expr::Group(g) => is_nonzero_int_literal(&g.expr),
+        _ => false,
+    }
+}
+
+/// The outer attributes that apply to `expr`, following `syn`'s placement
+/// rule for a binary expression: `ExprBinary` itself never carries outer
+/// attrs, since the parser attaches a leading attribute to the leftmost
+/// operand before folding the binary chain around it. Recurses through that
+/// chain so `#[sanctify::allow(arithmetic_overflow)] a + b + c` is read off
+/// `a`, however deep the nesting.
+fn leading_attrs(expr: &ExprBinary) -> &[Attribute] {
+    match expr.left.as_ref() {
+        Expr::Binary(b) => leading_attrs(b),
+        Expr::Paren(p) => &p.attrs,
+        Expr::Group(g) => &g.attrs,
+        Expr::Path(p) => &p.attrs,
+        Expr::Lit(l) => &l.attrs,
+        Expr::Unary(u) => &u.attrs,
+        Expr::MethodCall(m) => &m.attrs,
+        Expr::Call(c) => &c.attrs,
+        Expr::Field(f) => &f.attrs,
+        Expr::Index(idx) => &idx.attrs,
+        Expr::Reference(r) => &r.attrs,
+        _ => &[],
+    }
+}
+
+/// A storage key as written at one call site, with the domain it targets.
+/// `repr` is the literal identity of the key (source string, or variant path
+/// plus its literal arguments) — two keys collide only when `repr` matches.
+struct StorageKey {
+    domain: String,
+    repr: String,
+    span: Span,
+}
+
+/// Collects every storage-key construction fed to an `instance`/`persistent`/
+/// `temporary` accessor so collisions can be detected per domain.
+struct StorageVisitor {
+    keys: Vec<StorageKey>,
+}
+
+impl<'ast> Visit<'ast> for StorageVisitor {
+    fn visit_expr_method_call(&mut self, i: &'ast ExprMethodCall) {
+        let method = i.method.to_string();
+        if matches!(
+            method.as_str(),
+            "set" | "get" | "has" | "remove" | "update" | "extend_ttl" | "bump"
+        ) {
+            if let Some(domain) = storage_domain(&i.receiver) {
+                if let Some(arg) = i.args.first() {
+                    if let Some((repr, span)) = extract_key(arg) {
+                        self.keys.push(StorageKey { domain, repr, span });
+                    }
+                }
+            }
+        }
+        visit::visit_expr_method_call(self, i);
+    }
+}
+
+/// The storage domain named by the receiver of a `set`/`get`/... call, e.g.
+/// `env.storage().persistent()` -> `"persistent"`.
+fn storage_domain(expr: &Expr) -> Option<String> {
+    if let Expr::MethodCall(mc) = expr {
+        return match mc.method.to_string().as_str() {
+            "instance" => Some("instance".to_string()),
+            "persistent" => Some("persistent".to_string()),
+            "temporary" => Some("temporary".to_string()),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Resolve a storage-key expression to `(repr, span)`. Handles
+/// `symbol_short!("x")`, `Symbol::new(&env, "x")`, and enum `DataKey` variants.
+fn extract_key(expr: &Expr) -> Option<(String, Span)> {
+    match expr {
+        Expr::Reference(r) => extract_key(&r.expr),
+        Expr::Paren(p) => extract_key(&p.expr),
+        Expr::Group(g) => extract_key(&g.expr),
+        Expr::Macro(m) if m.mac.path.is_ident("symbol_short") || m.mac.path.is_ident("symbol") => {
+            let lit = parse_str::<LitStr>(&m.mac.tokens.to_string()).ok()?;
+            Some((lit.value(), Span::of(m.mac.path.span())))
+        }
+        Expr::Call(c) => {
+            if let Expr::Path(p) = c.func.as_ref() {
+                let last = p.path.segments.last().map(|s| s.ident.to_string());
+                if last.as_deref() == Some("new") && path_contains(&p.path, "Symbol") {
+                    // Symbol::new(&env, "literal")
+                    for a in &c.args {
+                        if let Expr::Lit(l) = a {
+                            if let Lit::Str(s) = &l.lit {
+                                return Some((s.value(), Span::of(s.span())));
+                            }
+                        }
+                    }
+                    return None;
+                }
+                // Tuple/struct enum variant, e.g. `DataKey::Balance(user)`. A
+                // variant is only comparable across call sites when every
+                // argument is a literal we can read back out: `Balance(alice)`
+                // and `Balance(bob)` key different runtime values even though
+                // they share a variant path, and we can't prove them equal or
+                // distinct from the identifier alone, so any non-literal
+                // argument makes the whole call site untrackable rather than
+                // falsely collapsing it onto the bare variant name.
+                let mut arg_reprs = Vec::with_capacity(c.args.len());
+                for a in &c.args {
+                    match a {
+                        Expr::Lit(l) => arg_reprs.push(literal_repr(&l.lit)),
+                        _ => return None,
+                    }
+                }
+                let repr = if arg_reprs.is_empty() {
+                    path_string(&p.path)
+                } else {
+                    format!("{}({})", path_string(&p.path), arg_reprs.join(", "))
+                };
+                return Some((repr, Span::of(p.path.span())));
+            }
+            None
+        }
+        // Unit enum variant, e.g. `DataKey::Admin`.
+        Expr::Path(p) if p.path.segments.len() > 1 => {
+            Some((path_string(&p.path), Span::of(p.path.span())))
+        }
+        _ => None,
+    }
+}
+
+/// Render a literal back to the source text it would need to match, for
+/// comparing enum-variant arguments (`DataKey::Counter(5)` vs `DataKey::Counter(6)`).
+fn literal_repr(lit: &Lit) -> String {
+    match lit {
+        Lit::Str(s) => format!("{:?}", s.value()),
+        Lit::ByteStr(s) => format!("{:?}", s.value()),
+        Lit::Byte(b) => b.value().to_string(),
+        Lit::Char(c) => format!("{:?}", c.value()),
+        Lit::Int(i) => i.base10_digits().to_string(),
+        Lit::Float(f) => f.base10_digits().to_string(),
+        Lit::Bool(b) => b.value.to_string(),
+        _ => lit.to_token_stream().to_string(),
+    }
+}
+
+fn path_contains(path: &Path, ident: &str) -> bool {
+    path.segments.iter().any(|s| s.ident == ident)
+}
+
+fn path_string(path: &Path) -> String {
+    path.segments
+        .iter()
+        .map(|s| s.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
 }
 
 pub struct Analyzer {
     pub strict_mode: bool,
     pub ledger_limit: usize,
+    /// Assumed element count for unbounded containers (`Vec`/`Map`) when
+    /// estimating their contribution to an entry's on-ledger size.
+    pub default_capacity: usize,
+    /// Global lint levels keyed by lint name; unset lints default to `warn`.
+    pub lint_levels: HashMap<String, LintLevel>,
 }
 
 impl Analyzer {
     pub fn new(strict_mode: bool) -> Self {
-        Self { 
+        Self {
             strict_mode,
             ledger_limit: 64000, // Default 64KB warning threshold
+            default_capacity: 16,
+            lint_levels: HashMap::new(),
+        }
+    }
+
+    /// Set the global level for a lint, overriding the `warn` default.
+    pub fn set_lint_level(&mut self, lint: &str, level: LintLevel) {
+        self.lint_levels.insert(lint.to_string(), level);
+    }
+
+    /// Resolve the effective level for `lint` given the enclosing inline-attribute
+    /// scope (nearest last). `forbid` — global or anywhere in scope — wins over
+    /// everything; otherwise the nearest inline level applies, then the global
+    /// setting, then the `warn` default. `strict_mode` promotes `warn` to `deny`.
+    fn effective_level(&self, lint: &str, scope: &[HashMap<String, LintLevel>]) -> LintLevel {
+        if self.lint_levels.get(lint) == Some(&LintLevel::Forbid)
+            || scope.iter().any(|m| m.get(lint) == Some(&LintLevel::Forbid))
+        {
+            return LintLevel::Forbid;
+        }
+        let base = scope
+            .iter()
+            .rev()
+            .find_map(|m| m.get(lint).copied())
+            .or_else(|| self.lint_levels.get(lint).copied())
+            .unwrap_or(LintLevel::Warn);
+        if self.strict_mode && base == LintLevel::Warn {
+            LintLevel::Deny
+        } else {
+            base
         }
     }
 
@@ -84,9 +652,42 @@ impl Analyzer {
         vec![]
     }
 
-    pub fn check_storage_collisions(&self, _keys: Vec<String>) -> bool {
-        // Placeholder for collision detection
-        false
+    /// Walk the source for storage-key construction and flag two distinct keys
+    /// that land on the same slot. Collisions are scoped per storage domain, so
+    /// an `instance` key never clashes with a `persistent` one. Keys are
+    /// compared by literal identity only — the source string for symbols, or
+    /// the variant path plus its literal arguments for enum `DataKey`s — so a
+    /// variant keyed by a runtime value (e.g. `DataKey::Balance(user)`) is
+    /// never falsely flagged against another call site using the same variant.
+    /// Distinct symbol strings whose packed `Symbol` encodings coincide are not
+    /// detected.
+    pub fn check_storage_collisions(&self, source: &str) -> Vec<CollisionWarning> {
+        let file = match parse_str::<File>(source) {
+            Ok(f) => f,
+            Err(_) => return vec![],
+        };
+
+        let mut visitor = StorageVisitor { keys: Vec::new() };
+        visitor.visit_file(&file);
+
+        let mut warnings = Vec::new();
+        let keys = &visitor.keys;
+        for a in 0..keys.len() {
+            for b in (a + 1)..keys.len() {
+                if keys[a].domain != keys[b].domain {
+                    continue;
+                }
+                if keys[a].repr == keys[b].repr {
+                    warnings.push(CollisionWarning {
+                        key_a: keys[a].repr.clone(),
+                        key_b: keys[b].repr.clone(),
+                        storage_domain: keys[a].domain.clone(),
+                        span: keys[b].span,
+                    });
+                }
+            }
+        }
+        warnings
     }
 
     pub fn analyze_ledger_size(&self, source: &str) -> Vec<SizeWarning> {
@@ -95,9 +696,10 @@ impl Analyzer {
             Err(_) => return vec![],
         };
         
+        let index = TypeIndex::build(&file);
         let mut warnings = Vec::new();
 
-        for item in file.items {
+        for item in &file.items {
             if let Item::Struct(s) = item {
                 let has_contracttype = s.attrs.iter().any(|attr| {
                     match &attr.meta {
@@ -107,12 +709,22 @@ impl Analyzer {
                 });
 
                 if has_contracttype {
-                    let size = self.estimate_struct_size(&s);
+                    let scope = vec![parse_lint_attrs(&s.attrs)];
+                    let level = self.effective_level(LEDGER_SIZE_LINT, &scope);
+                    if level == LintLevel::Allow {
+                        continue;
+                    }
+                    let mut visited = vec![s.ident.to_string()];
+                    let breakdown = self.struct_field_sizes(s, &index, &mut visited);
+                    let size: usize = breakdown.iter().map(|f| f.bytes).sum();
                     if size > self.ledger_limit || (self.strict_mode && size > self.ledger_limit / 2) {
                         warnings.push(SizeWarning {
                             struct_name: s.ident.to_string(),
                             estimated_size: size,
                             limit: self.ledger_limit,
+                            breakdown,
+                            span: Span::of(s.ident.span()),
+                            level,
                         });
                     }
                 }
@@ -127,49 +739,292 @@ impl Analyzer {
             Err(_) => return vec![],
         };
         
-        let mut visitor = UnsafeVisitor { patterns: Vec::new() };
+        let mut visitor = UnsafeVisitor {
+            analyzer: self,
+            patterns: Vec::new(),
+            scope: Vec::new(),
+            scan_macros: true,
+        };
         visitor.visit_file(&file);
         visitor.patterns
     }
 
-    fn estimate_struct_size(&self, s: &syn::ItemStruct) -> usize {
-        let mut total_size = 0;
+    /// Run every analyzer pass and lower each finding into the unified
+    /// [`Diagnostic`] stream, pass by pass. Unsafe patterns come in syntax-tree
+    /// walk order, so an enclosing expression is reported before the
+    /// expressions nested inside it.
+    pub fn run_all(&self, source: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for p in self.analyze_unsafe_patterns(source) {
+            let (message, suggestion) = match p.pattern_type {
+                PatternType::Panic => (
+                    "`panic!` traps the transaction; return a contract error instead".to_string(),
+                    None,
+                ),
+                PatternType::Unwrap => (
+                    "`unwrap()` panics on `None`/`Err`".to_string(),
+                    Some(Suggestion {
+                        message: "handle the empty/error case explicitly".to_string(),
+                        replacement: "unwrap_or_else(|| ...)".to_string(),
+                    }),
+                ),
+                PatternType::Expect => (
+                    "`expect()` panics on `None`/`Err`".to_string(),
+                    Some(Suggestion {
+                        message: "handle the empty/error case explicitly".to_string(),
+                        replacement: "ok_or(Error::...)?".to_string(),
+                    }),
+                ),
+                PatternType::ArithmeticOverflow => (
+                    "integer arithmetic traps on overflow".to_string(),
+                    p.suggestion.clone().map(|r| Suggestion {
+                        message: "use the checked variant and handle `None`".to_string(),
+                        replacement: r,
+                    }),
+                ),
+                PatternType::IndexPanic => (
+                    "indexing traps when the index is out of bounds".to_string(),
+                    p.suggestion.clone().map(|r| Suggestion {
+                        message: "use the fallible accessor".to_string(),
+                        replacement: r,
+                    }),
+                ),
+                PatternType::DivisionByZero => (
+                    "division/remainder traps when the divisor is zero".to_string(),
+                    p.suggestion.clone().map(|r| Suggestion {
+                        message: "use the checked variant and handle `None`".to_string(),
+                        replacement: r,
+                    }),
+                ),
+            };
+            diagnostics.push(Diagnostic {
+                severity: severity_for(p.level),
+                code: p.pattern_type.code().to_string(),
+                message,
+                span: p.span,
+                suggestion,
+            });
+        }
+
+        for w in self.analyze_ledger_size(source) {
+            diagnostics.push(Diagnostic {
+                severity: severity_for(w.level),
+                code: "S010".to_string(),
+                message: format!(
+                    "`{}` may exceed the ledger entry limit: estimated {} bytes, limit {}",
+                    w.struct_name, w.estimated_size, w.limit
+                ),
+                span: w.span,
+                suggestion: None,
+            });
+        }
+
+        // Unlike the unsafe-pattern and ledger-size passes, a collision is
+        // scoped to the empty (global-only) attribute scope: each warning
+        // spans two call sites, possibly in different functions with
+        // different inline `#[sanctify::allow(..)]` scopes, so there is no
+        // single enclosing item whose attrs could plausibly suppress just
+        // that one pair. `storage_collision` can only be set via
+        // `set_lint_level`, never inline.
+        let collision_level = self.effective_level(STORAGE_COLLISION_LINT, &[]);
+        if collision_level != LintLevel::Allow {
+            for c in self.check_storage_collisions(source) {
+                let message = if c.key_a == c.key_b {
+                    format!(
+                        "duplicate `{}` storage key in the {} domain",
+                        c.key_a, c.storage_domain
+                    )
+                } else {
+                    format!(
+                        "storage keys `{}` and `{}` collide in the {} domain",
+                        c.key_a, c.key_b, c.storage_domain
+                    )
+                };
+                diagnostics.push(Diagnostic {
+                    severity: severity_for(collision_level),
+                    code: "S020".to_string(),
+                    message,
+                    span: c.span,
+                    suggestion: None,
+                });
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Emit diagnostics as newline-delimited JSON, one object per line, so a
+    /// consumer can stream results incrementally.
+    pub fn to_json_lines(diagnostics: &[Diagnostic]) -> String {
+        let mut out = String::new();
+        for d in diagnostics {
+            if let Ok(line) = serde_json::to_string(d) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Per-field size breakdown for a struct, resolving user-defined field
+    /// types recursively. `visited` carries the chain of type names currently
+    /// being expanded so a self-referential type is charged as a pointer (8)
+    /// rather than recursing forever.
+    fn struct_field_sizes(
+        &self,
+        s: &ItemStruct,
+        index: &TypeIndex,
+        visited: &mut Vec<String>,
+    ) -> Vec<FieldSize> {
+        let mut sizes = Vec::new();
         match &s.fields {
             Fields::Named(fields) => {
                 for field in &fields.named {
-                    total_size += self.estimate_type_size(&field.ty);
+                    let name = field
+                        .ident
+                        .as_ref()
+                        .map(|i| i.to_string())
+                        .unwrap_or_default();
+                    sizes.push(FieldSize {
+                        field: name,
+                        bytes: self.estimate_type_size(&field.ty, index, visited),
+                    });
                 }
             }
             Fields::Unnamed(fields) => {
-                for field in &fields.unnamed {
-                    total_size += self.estimate_type_size(&field.ty);
+                for (i, field) in fields.unnamed.iter().enumerate() {
+                    sizes.push(FieldSize {
+                        field: i.to_string(),
+                        bytes: self.estimate_type_size(&field.ty, index, visited),
+                    });
                 }
             }
             Fields::Unit => {}
         }
-        total_size
-    }
-
-    fn estimate_type_size(&self, ty: &Type) -> usize {
-        match ty {
-            Type::Path(tp) => {
-                if let Some(segment) = tp.path.segments.last() {
-                    let ident = segment.ident.to_string();
-                    match ident.as_str() {
-                        "u32" | "i32" | "bool" => 4,
-                        "u64" | "i64" => 8,
-                        "u128" | "i128" | "I128" | "U128" => 16,
-                        "Address" => 32,
-                        "Bytes" | "BytesN" | "String" | "Symbol" => 64,
-                        "Vec" | "Map" => 128,
-                        _ => 32,
-                    }
-                } else {
-                    8
+        sizes
+    }
+
+    fn struct_size(&self, s: &ItemStruct, index: &TypeIndex, visited: &mut Vec<String>) -> usize {
+        self.struct_field_sizes(s, index, visited)
+            .iter()
+            .map(|f| f.bytes)
+            .sum()
+    }
+
+    /// An enum is its discriminant tag plus the largest variant, since only one
+    /// variant is live on ledger at a time.
+    fn enum_size(&self, e: &ItemEnum, index: &TypeIndex, visited: &mut Vec<String>) -> usize {
+        let widest = e
+            .variants
+            .iter()
+            .map(|v| match &v.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .iter()
+                    .map(|f| self.estimate_type_size(&f.ty, index, visited))
+                    .sum(),
+                Fields::Unnamed(fields) => fields
+                    .unnamed
+                    .iter()
+                    .map(|f| self.estimate_type_size(&f.ty, index, visited))
+                    .sum(),
+                Fields::Unit => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        4 + widest
+    }
+
+    fn estimate_type_size(&self, ty: &Type, index: &TypeIndex, visited: &mut Vec<String>) -> usize {
+        let tp = match ty {
+            Type::Path(tp) => tp,
+            _ => return 8,
+        };
+        let segment = match tp.path.segments.last() {
+            Some(s) => s,
+            None => return 8,
+        };
+        let ident = segment.ident.to_string();
+
+        match ident.as_str() {
+            "u32" | "i32" | "bool" => return 4,
+            "u64" | "i64" => return 8,
+            "u128" | "i128" | "I128" | "U128" => return 16,
+            "Address" => return 32,
+            "Bytes" | "String" | "Symbol" => return 64,
+            _ => {}
+        }
+
+        // Generic containers scale by their element size.
+        if let PathArguments::AngleBracketed(args) = &segment.arguments {
+            match ident.as_str() {
+                "Vec" => {
+                    let elem = self.first_type_arg(args, index, visited).unwrap_or(8);
+                    return self.default_capacity * elem;
                 }
+                "Map" => {
+                    let mut types = args.args.iter().filter_map(|a| match a {
+                        GenericArgument::Type(t) => Some(t),
+                        _ => None,
+                    });
+                    let k = types
+                        .next()
+                        .map(|t| self.estimate_type_size(t, index, visited))
+                        .unwrap_or(8);
+                    let v = types
+                        .next()
+                        .map(|t| self.estimate_type_size(t, index, visited))
+                        .unwrap_or(8);
+                    return self.default_capacity * (k + v);
+                }
+                "BytesN" => return self.first_const_arg(args).unwrap_or(64),
+                _ => {}
             }
-            _ => 8,
         }
+
+        // User-defined types resolve through the index; a re-entered type is a
+        // pointer-sized back-reference.
+        if visited.iter().any(|v| v == &ident) {
+            return 8;
+        }
+        if let Some(s) = index.structs.get(&ident) {
+            visited.push(ident.clone());
+            let size = self.struct_size(s, index, visited);
+            visited.pop();
+            return size;
+        }
+        if let Some(e) = index.enums.get(&ident) {
+            visited.push(ident.clone());
+            let size = self.enum_size(e, index, visited);
+            visited.pop();
+            return size;
+        }
+
+        // Unknown external type.
+        32
+    }
+
+    fn first_type_arg(
+        &self,
+        args: &syn::AngleBracketedGenericArguments,
+        index: &TypeIndex,
+        visited: &mut Vec<String>,
+    ) -> Option<usize> {
+        args.args.iter().find_map(|a| match a {
+            GenericArgument::Type(t) => Some(self.estimate_type_size(t, index, visited)),
+            _ => None,
+        })
+    }
+
+    fn first_const_arg(&self, args: &syn::AngleBracketedGenericArguments) -> Option<usize> {
+        args.args.iter().find_map(|a| match a {
+            GenericArgument::Const(Expr::Lit(lit)) => match &lit.lit {
+                Lit::Int(n) => n.base10_parse::<usize>().ok(),
+                _ => None,
+            },
+            _ => None,
+        })
     }
 }
 
@@ -205,4 +1060,260 @@ mod tests {
         let patterns = analyzer.analyze_unsafe_patterns(source);
         assert_eq!(patterns.len(), 2);
     }
+
+    #[test]
+    fn test_recursive_struct_size() {
+        let source = r#"
+            #[contracttype]
+            pub struct Inner {
+                a: u128,
+                b: u128,
+            }
+            #[contracttype]
+            pub struct Outer {
+                inner: Inner,
+                flag: bool,
+            }
+        "#;
+        let mut analyzer = Analyzer::new(false);
+        analyzer.ledger_limit = 1; // force reporting
+        let warnings = analyzer.analyze_ledger_size(source);
+        let outer = warnings.iter().find(|w| w.struct_name == "Outer").unwrap();
+        // inner = 16 + 16, flag = 4
+        assert_eq!(outer.estimated_size, 36);
+        assert_eq!(outer.breakdown.len(), 2);
+    }
+
+    #[test]
+    fn test_enum_and_container_sizes() {
+        let source = r#"
+            #[contracttype]
+            pub enum Kind {
+                Small(u32),
+                Big(u128, u128),
+            }
+            #[contracttype]
+            pub struct Holder {
+                items: Vec<u64>,
+                hash: BytesN<32>,
+                kind: Kind,
+            }
+        "#;
+        let mut analyzer = Analyzer::new(false);
+        analyzer.ledger_limit = 1;
+        analyzer.default_capacity = 4;
+        let warnings = analyzer.analyze_ledger_size(source);
+        let holder = warnings.iter().find(|w| w.struct_name == "Holder").unwrap();
+        // items = 4 * 8, hash = 32, kind = 4 + max(4, 32)
+        assert_eq!(holder.estimated_size, 32 + 32 + 36);
+    }
+
+    #[test]
+    fn test_run_all_json_lines() {
+        let source = r#"
+            pub fn test() {
+                let x: Option<i32> = None;
+                x.unwrap();
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        let diags = analyzer.run_all(source);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].code, "S002");
+        let json = Analyzer::to_json_lines(&diags);
+        assert_eq!(json.lines().count(), 1);
+        assert!(json.contains("\"code\":\"S002\""));
+    }
+
+    #[test]
+    fn test_inline_allow_suppresses() {
+        let source = r#"
+            #[sanctify::allow(unsafe_panic)]
+            pub fn test() {
+                panic!("accepted");
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.analyze_unsafe_patterns(source).is_empty());
+    }
+
+    #[test]
+    fn test_inline_allow_suppresses_statement_level() {
+        let source = r#"
+            pub fn test() {
+                #[sanctify::allow(unsafe_panic)]
+                panic!("accepted");
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.analyze_unsafe_patterns(source).is_empty());
+    }
+
+    #[test]
+    fn test_inline_allow_suppresses_arithmetic_overflow_expr() {
+        let source = r#"
+            pub fn test(a: u32, b: u32) {
+                #[sanctify::allow(arithmetic_overflow)]
+                a + b;
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.analyze_unsafe_patterns(source).is_empty());
+    }
+
+    #[test]
+    fn test_inline_allow_suppresses_division_by_zero_expr() {
+        let source = r#"
+            pub fn test(a: u32, b: u32) {
+                #[sanctify::allow(division_by_zero)]
+                a / b;
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.analyze_unsafe_patterns(source).is_empty());
+    }
+
+    #[test]
+    fn test_inline_allow_suppresses_index_panic_expr() {
+        let source = r#"
+            pub fn test(v: Vec<u32>, i: usize) {
+                #[sanctify::allow(index_panic)]
+                v[i];
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.analyze_unsafe_patterns(source).is_empty());
+    }
+
+    #[test]
+    fn test_forbid_overrides_inner_allow() {
+        let source = r#"
+            #[sanctify::allow(unsafe_panic)]
+            pub fn test() {
+                panic!("still reported");
+            }
+        "#;
+        let mut analyzer = Analyzer::new(false);
+        analyzer.set_lint_level("unsafe_panic", LintLevel::Forbid);
+        let patterns = analyzer.analyze_unsafe_patterns(source);
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].level, LintLevel::Forbid);
+    }
+
+    #[test]
+    fn test_strict_mode_promotes_to_error() {
+        let source = r#"
+            pub fn test() {
+                let x: Option<i32> = None;
+                x.unwrap();
+            }
+        "#;
+        let analyzer = Analyzer::new(true);
+        let diags = analyzer.run_all(source);
+        assert_eq!(diags[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_arithmetic_and_index_detection() {
+        let source = r#"
+            pub fn test(a: u32, b: u32, v: Vec<u32>, i: usize) -> u32 {
+                let _s = a + b;
+                let _d = a / b;
+                let _safe = a / 2;
+                let _x = v[i];
+                _s
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        let patterns = analyzer.analyze_unsafe_patterns(source);
+        let kinds: Vec<_> = patterns.iter().map(|p| p.pattern_type.code()).collect();
+        assert!(kinds.contains(&"S004")); // a + b
+        assert!(kinds.contains(&"S006")); // a / b (non-literal divisor)
+        assert!(kinds.contains(&"S005")); // v[i]
+        // `a / 2` has a nonzero literal divisor: overflow, not divide-by-zero.
+        assert_eq!(kinds.iter().filter(|c| **c == "S006").count(), 1);
+    }
+
+    #[test]
+    fn test_unwrap_inside_macro_body() {
+        let source = r#"
+            pub fn test() {
+                let x: Option<i32> = None;
+                assert!(x.unwrap() == 1);
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        let patterns = analyzer.analyze_unsafe_patterns(source);
+        let from_macro: Vec<_> = patterns
+            .iter()
+            .filter(|p| p.origin_macro.as_deref() == Some("assert"))
+            .collect();
+        assert_eq!(from_macro.len(), 1);
+        assert!(matches!(from_macro[0].pattern_type, PatternType::Unwrap));
+    }
+
+    #[test]
+    fn test_storage_collision_same_domain() {
+        let source = r#"
+            pub fn store(env: Env) {
+                env.storage().persistent().set(&symbol_short!("bal"), &1);
+                env.storage().persistent().set(&symbol_short!("bal"), &2);
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        let collisions = analyzer.check_storage_collisions(source);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].storage_domain, "persistent");
+        assert_eq!(collisions[0].key_a, "bal");
+    }
+
+    #[test]
+    fn test_storage_no_collision_across_domains() {
+        let source = r#"
+            pub fn store(env: Env) {
+                env.storage().instance().set(&symbol_short!("bal"), &1);
+                env.storage().persistent().set(&symbol_short!("bal"), &2);
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.check_storage_collisions(source).is_empty());
+    }
+
+    #[test]
+    fn test_storage_no_collision_same_variant_different_identifiers() {
+        let source = r#"
+            pub fn run(env: Env, alice: Address, bob: Address) {
+                env.storage().persistent().set(&DataKey::Balance(alice), &1);
+                env.storage().persistent().set(&DataKey::Balance(bob), &2);
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.check_storage_collisions(source).is_empty());
+    }
+
+    #[test]
+    fn test_storage_collision_same_variant_same_literal_argument() {
+        let source = r#"
+            pub fn run(env: Env) {
+                env.storage().persistent().set(&DataKey::Counter(5), &1);
+                env.storage().persistent().set(&DataKey::Counter(5), &2);
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        let collisions = analyzer.check_storage_collisions(source);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].key_a, "DataKey::Counter(5)");
+    }
+
+    #[test]
+    fn test_storage_no_collision_same_variant_different_literal_argument() {
+        let source = r#"
+            pub fn run(env: Env) {
+                env.storage().persistent().set(&DataKey::Counter(5), &1);
+                env.storage().persistent().set(&DataKey::Counter(6), &2);
+            }
+        "#;
+        let analyzer = Analyzer::new(false);
+        assert!(analyzer.check_storage_collisions(source).is_empty());
+    }
 }